@@ -1,13 +1,197 @@
-use portable_pty::{native_pty_system, CommandBuilder, PtyPair, PtySize};
-use std::{fmt::Display, io::Write, sync::mpsc, time::Duration};
+use portable_pty::{native_pty_system, Child, CommandBuilder, MasterPty, PtyPair, PtySize};
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    io::Write,
+    path::PathBuf,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
 
-const WIDTH: usize = 80;
-const HEIGHT: usize = 24;
+/// The default terminal geometry used by `new`/`nu`. Use
+/// `SimpleTerminal::with_size`/`nu_with_size` for other sizes, or `resize`
+/// to change the size of an existing terminal.
+const DEFAULT_WIDTH: usize = 80;
+const DEFAULT_HEIGHT: usize = 24;
+
+/// The number of scrolled-off lines kept in `SimpleTerminal::scrollback` by
+/// default. Use `SimpleTerminal::with_scrollback_capacity` to override it.
+const DEFAULT_SCROLLBACK_CAPACITY: usize = 1000;
+
+/// How often `wait_until` checks the predicate while polling the PTY for
+/// more output.
+const WAIT_UNTIL_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// Requires a `bitflags` dependency in this crate's Cargo.toml (e.g.
+// `bitflags.workspace = true`); this source snapshot has no manifest to add
+// it to.
+bitflags::bitflags! {
+    /// Text attribute bits set by SGR parameters `1`/`2`/`3`/`4`/`7`/`9`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct Attrs: u8 {
+        const BOLD          = 0b0000_0001;
+        const DIM           = 0b0000_0010;
+        const ITALIC        = 0b0000_0100;
+        const UNDERLINE     = 0b0000_1000;
+        const REVERSE       = 0b0001_0000;
+        const STRIKETHROUGH = 0b0010_0000;
+    }
+}
+
+/// A terminal color, covering the default color, the 8 base and 8 bright
+/// ANSI colors, 256-color palette indices, and 24-bit truecolor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Color {
+    #[default]
+    Default,
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    BrightBlack,
+    BrightRed,
+    BrightGreen,
+    BrightYellow,
+    BrightBlue,
+    BrightMagenta,
+    BrightCyan,
+    BrightWhite,
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    fn base(n: u16) -> Self {
+        match n {
+            0 => Color::Black,
+            1 => Color::Red,
+            2 => Color::Green,
+            3 => Color::Yellow,
+            4 => Color::Blue,
+            5 => Color::Magenta,
+            6 => Color::Cyan,
+            _ => Color::White,
+        }
+    }
+
+    fn bright(n: u16) -> Self {
+        match n {
+            0 => Color::BrightBlack,
+            1 => Color::BrightRed,
+            2 => Color::BrightGreen,
+            3 => Color::BrightYellow,
+            4 => Color::BrightBlue,
+            5 => Color::BrightMagenta,
+            6 => Color::BrightCyan,
+            _ => Color::BrightWhite,
+        }
+    }
+}
+
+/// A single screen cell: a character plus the pen state it was printed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub ch: char,
+    pub fg: Color,
+    pub bg: Color,
+    pub attrs: Attrs,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            ch: ' ',
+            fg: Color::Default,
+            bg: Color::Default,
+            attrs: Attrs::empty(),
+        }
+    }
+}
+
+/// The SGR state that `print` stamps onto each cell as it's written, updated
+/// by `csi_dispatch`'s handling of the `m` (Select Graphic Rendition) action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct Pen {
+    fg: Color,
+    bg: Color,
+    attrs: Attrs,
+}
+
+/// One of the OSC 133 shell-integration markers delimiting a prompt cycle,
+/// as recorded by `osc_dispatch`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellMarker {
+    /// OSC 133;A -- the prompt is about to be drawn.
+    PromptStart,
+    /// OSC 133;B -- the user's command starts here.
+    CommandStart,
+    /// OSC 133;C -- the command's output starts here.
+    OutputStart,
+    /// OSC 133;D -- the command finished.
+    CommandFinished,
+}
+
+/// A key press, encoded by `SimpleTerminal::send_key` into the byte sequence
+/// a real terminal would send for it. This gives tests a readable,
+/// cross-platform way to drive Nushell without remembering raw escape codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Tab,
+    BackTab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Ctrl(char),
+    Alt(char),
+    Esc,
+}
+
+impl Key {
+    fn encode(self, buf: &mut Vec<u8>) {
+        let mut char_bytes = [0u8; 4];
+        match self {
+            Key::Char(c) => buf.extend_from_slice(c.encode_utf8(&mut char_bytes).as_bytes()),
+            Key::Enter => buf.push(b'\r'),
+            Key::Tab => buf.push(b'\t'),
+            Key::BackTab => buf.extend_from_slice(b"\x1b[Z"),
+            Key::Backspace => buf.push(0x7f),
+            Key::Delete => buf.extend_from_slice(b"\x1b[3~"),
+            Key::Left => buf.extend_from_slice(b"\x1b[D"),
+            Key::Right => buf.extend_from_slice(b"\x1b[C"),
+            Key::Up => buf.extend_from_slice(b"\x1b[A"),
+            Key::Down => buf.extend_from_slice(b"\x1b[B"),
+            Key::Home => buf.extend_from_slice(b"\x1b[H"),
+            Key::End => buf.extend_from_slice(b"\x1b[F"),
+            Key::PageUp => buf.extend_from_slice(b"\x1b[5~"),
+            Key::PageDown => buf.extend_from_slice(b"\x1b[6~"),
+            Key::Ctrl(c) => buf.push(c.to_ascii_lowercase() as u8 & 0x1f),
+            Key::Alt(c) => {
+                buf.push(0x1b);
+                buf.extend_from_slice(c.encode_utf8(&mut char_bytes).as_bytes());
+            }
+            Key::Esc => buf.push(0x1b),
+        }
+    }
+}
 
 /// A simple terminal emulator for testing purposes. It implements
-/// `vte::Perform`, so you can connect it to the parser directly. It doesn't
-/// support colors or scrollback. Cursor movement operates on Unicode Scalar
-/// Values. The window size is fixed to 80x24.
+/// `vte::Perform`, so you can connect it to the parser directly. Cursor
+/// movement operates on Unicode Scalar Values. The window size defaults to
+/// 80x24 (see `new`/`nu`), but can be set with `with_size`/`nu_with_size` and
+/// changed later with `resize`.
 ///
 /// The following ANSI codes are supported:
 /// * 0x0A               (Line Feed)
@@ -19,40 +203,332 @@ const HEIGHT: usize = 24;
 /// * CSI $x    J        (Erase in Display)
 /// * CSI $x    K        (Erase in Line)
 /// * CSI $x    n        (Device Status Report)
+/// * CSI $x... m        (Select Graphic Rendition)
+/// * CSI ? 1049/47 h/l  (Alternate Screen Buffer)
+/// * CSI $t;$b r        (Set Top and Bottom Margins, aka DECSTBM)
+/// * CSI $x    L        (Insert Lines)
+/// * CSI $x    M        (Delete Lines)
+/// * CSI $x    @        (Insert Characters)
+/// * CSI $x    P        (Delete Characters)
+/// * CSI $x    S        (Scroll Up)
+/// * CSI $x    T        (Scroll Down)
 pub struct SimpleTerminal {
     pub cursor: (usize, usize),
     pub saved_cursor: (usize, usize),
-    pub buffer: [[char; WIDTH]; HEIGHT],
+    pub buffer: Vec<Vec<Cell>>,
     pub writer: Box<dyn Write + Send>,
+    width: usize,
+    height: usize,
+    pen: Pen,
+    scrollback: VecDeque<Vec<Cell>>,
+    scrollback_capacity: usize,
+    parser: vte::Parser,
+    // Only set for terminals created via `nu`. Lets `wait_until` pull more
+    // PTY output on demand instead of `nu` guessing how long to wait upfront,
+    // and lets `resize` apply the new `PtySize` to the child process.
+    rx: Option<mpsc::Receiver<Vec<u8>>>,
+    child: Option<Box<dyn Child + Send + Sync>>,
+    master: Option<Box<dyn MasterPty + Send>>,
+    current_dir: Option<PathBuf>,
+    title: Option<String>,
+    shell_markers: Vec<ShellMarker>,
+    // The primary screen, stashed here while the alternate screen (CSI ?
+    // 1049/47 h) is active. `None` means the primary screen is current.
+    alt_screen: Option<Vec<Vec<Cell>>>,
+    scroll_top: usize,
+    scroll_bottom: usize,
 }
 
 impl SimpleTerminal {
     pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self::with_size(DEFAULT_HEIGHT, DEFAULT_WIDTH, writer)
+    }
+
+    /// Like `new`, but with a configurable terminal geometry. Use `resize` to
+    /// change the geometry of a terminal that's already been created.
+    pub fn with_size(rows: usize, cols: usize, writer: Box<dyn Write + Send>) -> Self {
+        Self::with_size_and_scrollback_capacity(rows, cols, writer, DEFAULT_SCROLLBACK_CAPACITY)
+    }
+
+    /// Like `new`, but with a configurable scrollback capacity (the number
+    /// of scrolled-off lines to keep in `scrollback_lines()`).
+    pub fn with_scrollback_capacity(writer: Box<dyn Write + Send>, capacity: usize) -> Self {
+        Self::with_size_and_scrollback_capacity(DEFAULT_HEIGHT, DEFAULT_WIDTH, writer, capacity)
+    }
+
+    fn with_size_and_scrollback_capacity(
+        rows: usize,
+        cols: usize,
+        writer: Box<dyn Write + Send>,
+        capacity: usize,
+    ) -> Self {
         Self {
             cursor: (0, 0),
             saved_cursor: (0, 0),
-            buffer: [[' '; WIDTH]; HEIGHT],
+            buffer: vec![vec![Cell::default(); cols]; rows],
             writer,
+            width: cols,
+            height: rows,
+            pen: Pen::default(),
+            scrollback: VecDeque::new(),
+            scrollback_capacity: capacity,
+            parser: vte::Parser::new(),
+            rx: None,
+            child: None,
+            master: None,
+            current_dir: None,
+            title: None,
+            shell_markers: Vec::new(),
+            alt_screen: None,
+            scroll_top: 0,
+            scroll_bottom: rows - 1,
+        }
+    }
+
+    /// Reallocate a `rows` x `cols` grid, copying over whatever of `old`
+    /// still fits.
+    fn resized_grid(rows: usize, cols: usize, old: &[Vec<Cell>]) -> Vec<Vec<Cell>> {
+        let mut grid = vec![vec![Cell::default(); cols]; rows];
+        for (new_row, old_row) in grid.iter_mut().zip(old.iter()) {
+            let copy_cols = old_row.len().min(cols);
+            new_row[..copy_cols].copy_from_slice(&old_row[..copy_cols]);
+        }
+        grid
+    }
+
+    /// Reallocate the cell grid to `rows` x `cols`, preserving existing
+    /// content where it still fits and clamping the cursor into range. For
+    /// terminals created via `nu`/`nu_with_size`, also applies the new size
+    /// to the PTY so the child process receives a SIGWINCH.
+    ///
+    /// Also resizes the stashed alternate screen buffer, if one is active,
+    /// so a later `leave_alt_screen` can't restore a buffer whose dimensions
+    /// no longer match `self.width`/`self.height`.
+    pub fn resize(&mut self, rows: usize, cols: usize) {
+        self.buffer = Self::resized_grid(rows, cols, &self.buffer);
+        if let Some(alt_screen) = &self.alt_screen {
+            self.alt_screen = Some(Self::resized_grid(rows, cols, alt_screen));
+        }
+        self.width = cols;
+        self.height = rows;
+
+        self.cursor.0 = self.cursor.0.min(rows - 1);
+        self.cursor.1 = self.cursor.1.min(cols - 1);
+        self.saved_cursor.0 = self.saved_cursor.0.min(rows - 1);
+        self.saved_cursor.1 = self.saved_cursor.1.min(cols - 1);
+        self.scroll_top = 0;
+        self.scroll_bottom = rows - 1;
+
+        if let Some(master) = &self.master {
+            let _ = master.resize(PtySize {
+                rows: rows as u16,
+                cols: cols as u16,
+                pixel_width: 0,
+                pixel_height: 0,
+            });
+        }
+    }
+
+    /// The current working directory last reported via an OSC 7 sequence.
+    pub fn current_dir(&self) -> Option<&std::path::Path> {
+        self.current_dir.as_deref()
+    }
+
+    /// The window title last set via an OSC 0/2 sequence.
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// The OSC 133 prompt/command/output/exit markers seen so far, in the
+    /// order they were emitted.
+    pub fn shell_markers(&self) -> &[ShellMarker] {
+        &self.shell_markers
+    }
+
+    /// The lines that have scrolled off the top of the screen, oldest first.
+    pub fn scrollback_lines(&self) -> impl Iterator<Item = &[Cell]> {
+        self.scrollback.iter().map(|row| row.as_slice())
+    }
+
+    /// The full text of the terminal, scrollback followed by the visible
+    /// screen, one line per row.
+    pub fn full_text(&self) -> String {
+        let mut text = String::new();
+        for line in self.scrollback.iter().chain(self.buffer.iter()) {
+            for cell in line {
+                text.push(cell.ch);
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Shift the visible screen up by one line, pushing the evicted top row
+    /// into the scrollback (dropping the oldest line if at capacity).
+    fn scroll_up(&mut self) {
+        let evicted = self.buffer[0].clone();
+        self.buffer.rotate_left(1);
+        self.buffer.last_mut().unwrap().fill(Cell::default());
+
+        if self.scrollback_capacity == 0 {
+            return;
+        }
+        if self.scrollback.len() == self.scrollback_capacity {
+            self.scrollback.pop_front();
+        }
+        self.scrollback.push_back(evicted);
+    }
+
+    /// Swap to the alternate screen buffer, stashing the primary screen's
+    /// contents (and, for `?1049h`, the cursor position) until `leave_alt_screen`.
+    fn enter_alt_screen(&mut self, save_cursor: bool) {
+        if self.alt_screen.is_some() {
+            return;
+        }
+        if save_cursor {
+            self.saved_cursor = self.cursor;
         }
+        let blank = vec![vec![Cell::default(); self.width]; self.height];
+        self.alt_screen = Some(std::mem::replace(&mut self.buffer, blank));
+        self.cursor = (0, 0);
+    }
+
+    /// Restore the primary screen buffer saved by `enter_alt_screen`.
+    fn leave_alt_screen(&mut self, restore_cursor: bool) {
+        if let Some(saved) = self.alt_screen.take() {
+            self.buffer = saved;
+            if restore_cursor {
+                self.cursor = self.saved_cursor;
+            }
+        }
+    }
+
+    /// Insert `n` blank lines at the cursor row, shifting rows below it down
+    /// within the scroll region (rows pushed past the bottom margin are
+    /// discarded).
+    fn insert_lines(&mut self, n: usize) {
+        let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+        let row = self.cursor.0;
+        if row < top || row > bottom {
+            return;
+        }
+        for _ in 0..n.min(bottom - row + 1) {
+            for r in (row + 1..=bottom).rev() {
+                self.buffer[r] = self.buffer[r - 1].clone();
+            }
+            self.buffer[row].fill(Cell::default());
+        }
+    }
+
+    /// Delete `n` lines at the cursor row, pulling rows below it up within
+    /// the scroll region and filling the vacated bottom rows with blanks.
+    fn delete_lines(&mut self, n: usize) {
+        let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+        let row = self.cursor.0;
+        if row < top || row > bottom {
+            return;
+        }
+        for _ in 0..n.min(bottom - row + 1) {
+            for r in row..bottom {
+                self.buffer[r] = self.buffer[r + 1].clone();
+            }
+            self.buffer[bottom].fill(Cell::default());
+        }
+    }
+
+    /// Insert `n` blank characters at the cursor column, shifting the rest
+    /// of the row right (characters pushed past the last column are
+    /// discarded).
+    fn insert_chars(&mut self, n: usize) {
+        let row = self.cursor.0;
+        let col = self.cursor.1;
+        let n = n.min(self.width - col);
+        for c in (col + n..self.width).rev() {
+            self.buffer[row][c] = self.buffer[row][c - n];
+        }
+        self.buffer[row][col..col + n].fill(Cell::default());
+    }
+
+    /// Delete `n` characters at the cursor column, shifting the rest of the
+    /// row left and filling the vacated end of the row with blanks.
+    fn delete_chars(&mut self, n: usize) {
+        let row = self.cursor.0;
+        let col = self.cursor.1;
+        let n = n.min(self.width - col);
+        for c in col..self.width - n {
+            self.buffer[row][c] = self.buffer[row][c + n];
+        }
+        self.buffer[row][self.width - n..].fill(Cell::default());
+    }
+
+    /// Scroll the current scroll region up by `n` lines, discarding rows
+    /// from the top of the region and filling the bottom with blanks. When
+    /// the region spans the whole screen, this is the same as a natural
+    /// scroll, so the evicted rows go through `scroll_up` and land in
+    /// `scrollback` rather than being discarded.
+    fn scroll_region_up(&mut self, n: usize) {
+        let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+        let full_screen = top == 0 && bottom == self.height - 1;
+        for _ in 0..n.min(bottom - top + 1) {
+            if full_screen {
+                self.scroll_up();
+                continue;
+            }
+            for r in top..bottom {
+                self.buffer[r] = self.buffer[r + 1].clone();
+            }
+            self.buffer[bottom].fill(Cell::default());
+        }
+    }
+
+    /// Scroll the current scroll region down by `n` lines, discarding rows
+    /// from the bottom of the region and filling the top with blanks.
+    fn scroll_region_down(&mut self, n: usize) {
+        let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+        for _ in 0..n.min(bottom - top + 1) {
+            for r in (top + 1..=bottom).rev() {
+                self.buffer[r] = self.buffer[r - 1].clone();
+            }
+            self.buffer[top].fill(Cell::default());
+        }
+    }
+
+    /// Send a single key press to Nushell, as if it were typed in a terminal.
+    pub fn send_key(&mut self, key: Key) {
+        self.send_keys(&[key]);
+    }
+
+    /// Send a sequence of key presses to Nushell, as if they were typed in a
+    /// terminal.
+    pub fn send_keys(&mut self, keys: &[Key]) {
+        let mut buf = Vec::new();
+        for key in keys {
+            key.encode(&mut buf);
+        }
+        self.writer.write_all(&buf).unwrap();
     }
 
     /// Create a SimpleTerminal and connect it to an instance of Nushell. Within
     /// `func`, you can use `self.writer` to send keystrokes to Nushell, which
-    /// will appear to Nushell as if they were typed in a terminal. Returns the
-    /// final state of the terminal.
+    /// will appear to Nushell as if they were typed in a terminal, and
+    /// `self.wait_until` to block until the terminal shows what you expect.
+    /// Returns the final state of the terminal.
     ///
-    /// The Nushell process will be killed after 500ms of inactivity. This is
-    /// necessary because we have no way of knowing whether Nushell has finished
-    /// writing data to the terminal.
+    /// The Nushell process is killed when the returned terminal is dropped.
     ///
     /// Hint: If you want to press the Enter key, you should send `\r` (NOT
     /// `\n`) regardless of the platform.
     pub fn nu(func: impl FnOnce(&mut Self)) -> SimpleTerminal {
+        Self::nu_with_size(DEFAULT_HEIGHT, DEFAULT_WIDTH, func)
+    }
+
+    /// Like `nu`, but with a configurable terminal geometry.
+    pub fn nu_with_size(rows: usize, cols: usize, func: impl FnOnce(&mut Self)) -> SimpleTerminal {
         // Open a PTY pair.
         let PtyPair { slave, master } = native_pty_system()
             .openpty(PtySize {
-                rows: HEIGHT as u16,
-                cols: WIDTH as u16,
+                rows: rows as u16,
+                cols: cols as u16,
                 pixel_width: 0,
                 pixel_height: 0,
             })
@@ -61,7 +537,7 @@ impl SimpleTerminal {
         // Spawn Nushell to the slave end of the PTY.
         let mut cmd = CommandBuilder::new(crate::fs::executable_path());
         cmd.arg("--no-config-file");
-        let mut child = slave.spawn_command(cmd).unwrap();
+        let child = slave.spawn_command(cmd).unwrap();
 
         let mut reader = master.try_clone_reader().unwrap();
         let writer = master.take_writer().unwrap();
@@ -74,68 +550,201 @@ impl SimpleTerminal {
             let _ = tx.send(buf[..n].to_vec());
         });
 
-        let mut parser = vte::Parser::new();
-        let mut terminal = SimpleTerminal::new(writer);
+        let mut terminal = SimpleTerminal::with_size(rows, cols, writer);
+        terminal.rx = Some(rx);
+        terminal.child = Some(child);
+        terminal.master = Some(master);
+
+        // Wait for Nushell to print its initial prompt before handing control
+        // to `func`, so the first keystroke isn't swallowed by the startup
+        // banner.
+        terminal.wait_until(|term| term.cursor != (0, 0), Duration::from_secs(10));
 
-        // Wait for Nushell to initialize.
+        func(&mut terminal);
+
+        terminal
+    }
+
+    /// Drain PTY output into `self`, advancing the parser, until `pred(self)`
+    /// holds or `timeout` elapses. Returns whether `pred` ended up holding.
+    ///
+    /// Polls the background reader channel every `WAIT_UNTIL_POLL_INTERVAL`
+    /// rather than sleeping for a fixed duration, so assertions converge as
+    /// fast as Nushell actually renders instead of guessing an idle timeout.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called on a terminal not created via `nu`.
+    pub fn wait_until(
+        &mut self,
+        pred: impl Fn(&SimpleTerminal) -> bool,
+        timeout: Duration,
+    ) -> bool {
+        let deadline = Instant::now() + timeout;
         loop {
-            match rx.recv_timeout(Duration::from_millis(500)) {
-                Ok(buf) => {
-                    for c in buf {
-                        parser.advance(&mut terminal, c);
-                    }
+            if pred(self) {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+
+            let rx = self
+                .rx
+                .as_ref()
+                .expect("wait_until can only be called on a terminal created via `nu`");
+            if let Ok(buf) = rx.recv_timeout(WAIT_UNTIL_POLL_INTERVAL) {
+                let mut parser = std::mem::replace(&mut self.parser, vte::Parser::new());
+                for byte in buf {
+                    parser.advance(self, byte);
                 }
-                Err(_) => break,
+                self.parser = parser;
             }
         }
+    }
 
-        func(&mut terminal);
+    /// Apply the SGR params from a `m` CSI sequence to `self.pen`, handling
+    /// the extended `38;5;n` / `48;5;n` (256-color) and `38;2;r;g;b` /
+    /// `48;2;r;g;b` (truecolor) forms by consuming the extra params that
+    /// belong to the same sequence, and the per-attribute reset codes
+    /// (`22`/`23`/`24`/`27`/`29`) as well as the full `0` reset.
+    fn sgr_dispatch(&mut self, params: &vte::Params) {
+        let codes: Vec<u16> = params.iter().map(|p| p[0]).collect();
 
-        // Wait for Nushell to respond.
-        loop {
-            match rx.recv_timeout(Duration::from_millis(500)) {
-                Ok(buf) => {
-                    for c in buf {
-                        parser.advance(&mut terminal, c);
+        if codes.is_empty() {
+            self.pen = Pen::default();
+            return;
+        }
+
+        let mut i = 0;
+        while i < codes.len() {
+            match codes[i] {
+                0 => self.pen = Pen::default(),
+                1 => self.pen.attrs.insert(Attrs::BOLD),
+                2 => self.pen.attrs.insert(Attrs::DIM),
+                3 => self.pen.attrs.insert(Attrs::ITALIC),
+                4 => self.pen.attrs.insert(Attrs::UNDERLINE),
+                7 => self.pen.attrs.insert(Attrs::REVERSE),
+                9 => self.pen.attrs.insert(Attrs::STRIKETHROUGH),
+                22 => self.pen.attrs.remove(Attrs::BOLD | Attrs::DIM),
+                23 => self.pen.attrs.remove(Attrs::ITALIC),
+                24 => self.pen.attrs.remove(Attrs::UNDERLINE),
+                27 => self.pen.attrs.remove(Attrs::REVERSE),
+                29 => self.pen.attrs.remove(Attrs::STRIKETHROUGH),
+                n @ 30..=37 => self.pen.fg = Color::base(n - 30),
+                n @ 40..=47 => self.pen.bg = Color::base(n - 40),
+                n @ 90..=97 => self.pen.fg = Color::bright(n - 90),
+                n @ 100..=107 => self.pen.bg = Color::bright(n - 100),
+                39 => self.pen.fg = Color::Default,
+                49 => self.pen.bg = Color::Default,
+                target @ (38 | 48) => {
+                    i += 1;
+                    match codes.get(i) {
+                        Some(5) => {
+                            i += 1;
+                            if let Some(&n) = codes.get(i) {
+                                let color = Color::Indexed(n as u8);
+                                if target == 38 {
+                                    self.pen.fg = color;
+                                } else {
+                                    self.pen.bg = color;
+                                }
+                            }
+                        }
+                        Some(2) => {
+                            if let [Some(&r), Some(&g), Some(&b)] =
+                                [codes.get(i + 1), codes.get(i + 2), codes.get(i + 3)]
+                            {
+                                let color = Color::Rgb(r as u8, g as u8, b as u8);
+                                if target == 38 {
+                                    self.pen.fg = color;
+                                } else {
+                                    self.pen.bg = color;
+                                }
+                                i += 3;
+                            }
+                        }
+                        _ => {}
                     }
                 }
-                Err(_) => break,
+                _ => {}
             }
+            i += 1;
         }
+    }
+}
 
-        // Kill the Nushell process.
-        child.kill().unwrap();
-
-        terminal
+impl Drop for SimpleTerminal {
+    fn drop(&mut self) {
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+        }
     }
 }
 
 impl Display for SimpleTerminal {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for line in self.buffer {
-            let text: String = line.iter().collect();
+        for line in &self.buffer {
+            let text: String = line.iter().map(|cell| cell.ch).collect();
             writeln!(f, "{}", text)?;
         }
         Ok(())
     }
 }
 
+/// Decode the path out of an OSC 7 `file://host/path` URI, percent-decoding
+/// escaped bytes. Returns `None` if `uri` isn't a `file://` URI.
+fn parse_file_uri(uri: &str) -> Option<PathBuf> {
+    let rest = uri.strip_prefix("file://")?;
+    let path = &rest[rest.find('/')?..];
+    Some(PathBuf::from(percent_decode(path)))
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+
+    // Decode over the raw bytes rather than slicing `s` as a `str`: a stray
+    // `%` followed by a multibyte UTF-8 character (e.g. a malformed OSC 7
+    // URI) would otherwise land `i + 1..i + 3` off a char boundary and panic.
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 3 <= bytes.len() {
+            let hi = (bytes[i + 1] as char).to_digit(16);
+            let lo = (bytes[i + 2] as char).to_digit(16);
+            if let (Some(hi), Some(lo)) = (hi, lo) {
+                out.push((hi * 16 + lo) as u8);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
 impl vte::Perform for SimpleTerminal {
     fn print(&mut self, c: char) {
         log::debug!("SimpleTerminal/print: {}, cursor = {:?}", c, self.cursor);
 
-        self.buffer[self.cursor.0][self.cursor.1] = c;
+        self.buffer[self.cursor.0][self.cursor.1] = Cell {
+            ch: c,
+            fg: self.pen.fg,
+            bg: self.pen.bg,
+            attrs: self.pen.attrs,
+        };
 
-        if self.cursor.1 + 1 < WIDTH {
+        if self.cursor.1 + 1 < self.width {
             self.cursor.1 += 1;
         } else {
             self.cursor.1 = 0;
-            if self.cursor.0 + 1 < HEIGHT {
+            if self.cursor.0 + 1 < self.height {
                 self.cursor.0 += 1;
             } else {
                 // The screen is full. Shift everything up one line.
-                self.buffer.rotate_left(1);
-                self.buffer.last_mut().unwrap().fill(' ');
+                self.scroll_up();
             }
         }
     }
@@ -161,16 +770,16 @@ impl vte::Perform for SimpleTerminal {
         if action == 'B' {
             let n = params.into_iter().next().unwrap_or(&[1])[0] as usize;
             self.cursor.0 = self.cursor.0.saturating_add(n);
-            if self.cursor.0 >= HEIGHT {
-                self.cursor.0 = HEIGHT - 1;
+            if self.cursor.0 >= self.height {
+                self.cursor.0 = self.height - 1;
             }
         }
         // Handle Cursor Forward.
         if action == 'C' {
             let n = params.into_iter().next().unwrap_or(&[1])[0] as usize;
             self.cursor.1 = self.cursor.1.saturating_add(n);
-            if self.cursor.1 >= WIDTH {
-                self.cursor.1 = WIDTH - 1;
+            if self.cursor.1 >= self.width {
+                self.cursor.1 = self.width - 1;
             }
         }
         // Handle Cursor Backward.
@@ -187,7 +796,7 @@ impl vte::Perform for SimpleTerminal {
             if n == 0 {
                 self.cursor = (0, 0);
             }
-            if n > 0 && n <= HEIGHT && m > 0 && m <= WIDTH {
+            if n > 0 && n <= self.height && m > 0 && m <= self.width {
                 self.cursor.0 = n - 1;
                 self.cursor.1 = m - 1;
             }
@@ -197,22 +806,22 @@ impl vte::Perform for SimpleTerminal {
             let n = params.into_iter().next().unwrap_or(&[0])[0];
             // Handle Erase Below (default).
             if n == 0 {
-                self.buffer[self.cursor.0][self.cursor.1..].fill(' ');
-                for i in self.cursor.0 + 1..HEIGHT {
-                    self.buffer[i].fill(' ');
+                self.buffer[self.cursor.0][self.cursor.1..].fill(Cell::default());
+                for i in self.cursor.0 + 1..self.height {
+                    self.buffer[i].fill(Cell::default());
                 }
             }
             // Handle Erase Above.
             if n == 1 {
-                self.buffer[self.cursor.0][..=self.cursor.1].fill(' ');
+                self.buffer[self.cursor.0][..=self.cursor.1].fill(Cell::default());
                 for i in 0..self.cursor.0 {
-                    self.buffer[i].fill(' ');
+                    self.buffer[i].fill(Cell::default());
                 }
             }
             // Handle Erase All.
             if n == 2 {
-                for i in 0..HEIGHT {
-                    self.buffer[i].fill(' ');
+                for i in 0..self.height {
+                    self.buffer[i].fill(Cell::default());
                 }
             }
         }
@@ -221,15 +830,15 @@ impl vte::Perform for SimpleTerminal {
             let n = params.into_iter().next().unwrap_or(&[0])[0];
             // Handle Erase to Right (default).
             if n == 0 {
-                self.buffer[self.cursor.0][self.cursor.1..].fill(' ');
+                self.buffer[self.cursor.0][self.cursor.1..].fill(Cell::default());
             }
             // Handle Erase to Left.
             if n == 1 {
-                self.buffer[self.cursor.0][..=self.cursor.1].fill(' ');
+                self.buffer[self.cursor.0][..=self.cursor.1].fill(Cell::default());
             }
             // Handle Erase All.
             if n == 2 {
-                self.buffer[self.cursor.0].fill(' ');
+                self.buffer[self.cursor.0].fill(Cell::default());
             }
         }
         // Handle Device Status Report.
@@ -245,6 +854,73 @@ impl vte::Perform for SimpleTerminal {
                 self.writer.write_all(msg.as_bytes()).unwrap();
             }
         }
+        // Handle Select Graphic Rendition.
+        if action == 'm' {
+            self.sgr_dispatch(params);
+        }
+        // Handle Alternate Screen Buffer (DECSET/DECRST 1049 and 47).
+        if intermediates == [b'?'] && (action == 'h' || action == 'l') {
+            let n = params.into_iter().next().unwrap_or(&[0])[0];
+            if n == 1049 || n == 47 {
+                if action == 'h' {
+                    self.enter_alt_screen(n == 1049);
+                } else {
+                    self.leave_alt_screen(n == 1049);
+                }
+            }
+        }
+        // Handle Set Top and Bottom Margins (DECSTBM). Guarded on empty
+        // intermediates so we don't misparse `CSI ? Pm r` (XTRESTORE, restore
+        // DEC private modes) as a request to change the scroll region.
+        if action == 'r' && intermediates.is_empty() {
+            let mut iter = params.into_iter();
+            let top = iter.next().unwrap_or(&[1])[0] as usize;
+            let bottom = iter.next().unwrap_or(&[0])[0] as usize;
+            let top = top.saturating_sub(1).min(self.height - 1);
+            let bottom = if bottom == 0 {
+                self.height - 1
+            } else {
+                (bottom - 1).min(self.height - 1)
+            };
+            if top < bottom {
+                self.scroll_top = top;
+                self.scroll_bottom = bottom;
+            } else {
+                self.scroll_top = 0;
+                self.scroll_bottom = self.height - 1;
+            }
+            self.cursor = (0, 0);
+        }
+        // Handle Insert Lines.
+        if action == 'L' {
+            let n = params.into_iter().next().unwrap_or(&[1])[0].max(1) as usize;
+            self.insert_lines(n);
+        }
+        // Handle Delete Lines.
+        if action == 'M' {
+            let n = params.into_iter().next().unwrap_or(&[1])[0].max(1) as usize;
+            self.delete_lines(n);
+        }
+        // Handle Insert Characters.
+        if action == '@' {
+            let n = params.into_iter().next().unwrap_or(&[1])[0].max(1) as usize;
+            self.insert_chars(n);
+        }
+        // Handle Delete Characters.
+        if action == 'P' {
+            let n = params.into_iter().next().unwrap_or(&[1])[0].max(1) as usize;
+            self.delete_chars(n);
+        }
+        // Handle Scroll Up.
+        if action == 'S' {
+            let n = params.into_iter().next().unwrap_or(&[1])[0].max(1) as usize;
+            self.scroll_region_up(n);
+        }
+        // Handle Scroll Down.
+        if action == 'T' {
+            let n = params.into_iter().next().unwrap_or(&[1])[0].max(1) as usize;
+            self.scroll_region_down(n);
+        }
     }
 
     fn execute(&mut self, byte: u8) {
@@ -255,8 +931,12 @@ impl vte::Perform for SimpleTerminal {
         );
 
         // Handle Line Feed.
-        if byte == 0x0A && self.cursor.0 + 1 < HEIGHT {
-            self.cursor.0 += 1;
+        if byte == 0x0A {
+            if self.cursor.0 + 1 < self.height {
+                self.cursor.0 += 1;
+            } else {
+                self.scroll_up();
+            }
         }
         // Handle Carriage Return.
         if byte == 0x0D {
@@ -290,6 +970,45 @@ impl vte::Perform for SimpleTerminal {
             bell_terminated,
             self.cursor,
         );
+
+        let Some(code) = params
+            .first()
+            .and_then(|p| std::str::from_utf8(p).ok())
+            .and_then(|s| s.parse::<u32>().ok())
+        else {
+            return;
+        };
+
+        match code {
+            // Window title.
+            0 | 2 => {
+                if let Some(title) = params.get(1).and_then(|p| std::str::from_utf8(p).ok()) {
+                    self.title = Some(title.to_string());
+                }
+            }
+            // Current working directory, reported as a `file://host/path` URI.
+            7 => {
+                if let Some(uri) = params.get(1).and_then(|p| std::str::from_utf8(p).ok()) {
+                    if let Some(path) = parse_file_uri(uri) {
+                        self.current_dir = Some(path);
+                    }
+                }
+            }
+            // Shell-integration prompt markers.
+            133 => {
+                let marker = match params.get(1).copied() {
+                    Some(b"A") => Some(ShellMarker::PromptStart),
+                    Some(b"B") => Some(ShellMarker::CommandStart),
+                    Some(b"C") => Some(ShellMarker::OutputStart),
+                    Some(b"D") => Some(ShellMarker::CommandFinished),
+                    _ => None,
+                };
+                if let Some(marker) = marker {
+                    self.shell_markers.push(marker);
+                }
+            }
+            _ => {}
+        }
     }
 
     fn esc_dispatch(&mut self, intermediates: &[u8], ignore: bool, byte: u8) {
@@ -314,7 +1033,7 @@ impl vte::Perform for SimpleTerminal {
 
 #[cfg(test)]
 mod test {
-    use super::SimpleTerminal;
+    use super::{Attrs, Color, Key, ShellMarker, SimpleTerminal};
 
     fn emulate(input: &str) -> SimpleTerminal {
         let mut terminal = SimpleTerminal::new(Box::new(vec![]));
@@ -325,6 +1044,10 @@ mod test {
         terminal
     }
 
+    fn row_text(terminal: &SimpleTerminal, row: usize, range: std::ops::Range<usize>) -> String {
+        terminal.buffer[row][range].iter().map(|c| c.ch).collect()
+    }
+
     #[test]
     fn basic_cursor_movement() {
         let terminal = emulate("\x1b[10C\x1b[10B\x1b[5A\x1b[5D");
@@ -343,8 +1066,7 @@ mod test {
     #[test]
     fn print_at_cursor_position() {
         let terminal = emulate("\x1b[20;30Hfoo");
-        let text: String = terminal.buffer[19][29..32].iter().collect();
-        assert_eq!(text, "foo");
+        assert_eq!(row_text(&terminal, 19, 29..32), "foo");
         assert_eq!(terminal.cursor, (19, 32));
     }
 
@@ -352,45 +1074,45 @@ mod test {
     fn print_with_line_feed_and_carriage_return() {
         let terminal = emulate("AAA\r\nAAA\r\nAAA\r\n");
         assert_eq!(terminal.cursor, (3, 0));
-        assert_eq!(&terminal.buffer[0][..3], &['A', 'A', 'A']);
-        assert_eq!(&terminal.buffer[1][..3], &['A', 'A', 'A']);
-        assert_eq!(&terminal.buffer[2][..3], &['A', 'A', 'A']);
+        assert_eq!(row_text(&terminal, 0, 0..3), "AAA");
+        assert_eq!(row_text(&terminal, 1, 0..3), "AAA");
+        assert_eq!(row_text(&terminal, 2, 0..3), "AAA");
     }
 
     #[test]
     fn erase_in_display() {
         let terminal = emulate("AAA\r\nAAA\r\nAAA\x1b[2;2H\x1b[J");
-        assert_eq!(&terminal.buffer[0][..3], &['A', 'A', 'A']);
-        assert_eq!(&terminal.buffer[1][..3], &['A', ' ', ' ']);
-        assert_eq!(&terminal.buffer[2][..3], &[' ', ' ', ' ']);
+        assert_eq!(row_text(&terminal, 0, 0..3), "AAA");
+        assert_eq!(row_text(&terminal, 1, 0..3), "A  ");
+        assert_eq!(row_text(&terminal, 2, 0..3), "   ");
 
         let terminal = emulate("AAA\r\nAAA\r\nAAA\x1b[2;2H\x1b[1J");
-        assert_eq!(&terminal.buffer[0][..3], &[' ', ' ', ' ']);
-        assert_eq!(&terminal.buffer[1][..3], &[' ', ' ', 'A']);
-        assert_eq!(&terminal.buffer[2][..3], &['A', 'A', 'A']);
+        assert_eq!(row_text(&terminal, 0, 0..3), "   ");
+        assert_eq!(row_text(&terminal, 1, 0..3), "  A");
+        assert_eq!(row_text(&terminal, 2, 0..3), "AAA");
 
         let terminal = emulate("AAA\r\nAAA\r\nAAA\x1b[2;2H\x1b[2J");
-        assert_eq!(&terminal.buffer[0][..3], &[' ', ' ', ' ']);
-        assert_eq!(&terminal.buffer[1][..3], &[' ', ' ', ' ']);
-        assert_eq!(&terminal.buffer[2][..3], &[' ', ' ', ' ']);
+        assert_eq!(row_text(&terminal, 0, 0..3), "   ");
+        assert_eq!(row_text(&terminal, 1, 0..3), "   ");
+        assert_eq!(row_text(&terminal, 2, 0..3), "   ");
     }
 
     #[test]
     fn erase_in_line() {
         let terminal = emulate("AAA\r\nAAA\r\nAAA\x1b[2;2H\x1b[K");
-        assert_eq!(&terminal.buffer[0][..3], &['A', 'A', 'A']);
-        assert_eq!(&terminal.buffer[1][..3], &['A', ' ', ' ']);
-        assert_eq!(&terminal.buffer[2][..3], &['A', 'A', 'A']);
+        assert_eq!(row_text(&terminal, 0, 0..3), "AAA");
+        assert_eq!(row_text(&terminal, 1, 0..3), "A  ");
+        assert_eq!(row_text(&terminal, 2, 0..3), "AAA");
 
         let terminal = emulate("AAA\r\nAAA\r\nAAA\x1b[2;2H\x1b[1K");
-        assert_eq!(&terminal.buffer[0][..3], &['A', 'A', 'A']);
-        assert_eq!(&terminal.buffer[1][..3], &[' ', ' ', 'A']);
-        assert_eq!(&terminal.buffer[2][..3], &['A', 'A', 'A']);
+        assert_eq!(row_text(&terminal, 0, 0..3), "AAA");
+        assert_eq!(row_text(&terminal, 1, 0..3), "  A");
+        assert_eq!(row_text(&terminal, 2, 0..3), "AAA");
 
         let terminal = emulate("AAA\r\nAAA\r\nAAA\x1b[2;2H\x1b[2K");
-        assert_eq!(&terminal.buffer[0][..3], &['A', 'A', 'A']);
-        assert_eq!(&terminal.buffer[1][..3], &[' ', ' ', ' ']);
-        assert_eq!(&terminal.buffer[2][..3], &['A', 'A', 'A']);
+        assert_eq!(row_text(&terminal, 0, 0..3), "AAA");
+        assert_eq!(row_text(&terminal, 1, 0..3), "   ");
+        assert_eq!(row_text(&terminal, 2, 0..3), "AAA");
     }
 
     #[test]
@@ -398,4 +1120,322 @@ mod test {
         let terminal = emulate("\x1b[10;10H\x1b7\x1b[0H\x1b8");
         assert_eq!(terminal.cursor, (9, 9));
     }
+
+    #[test]
+    fn sgr_base_colors_and_attributes() {
+        let terminal = emulate("\x1b[1;4;31;42mA");
+        let cell = terminal.buffer[0][0];
+        assert_eq!(cell.ch, 'A');
+        assert_eq!(cell.fg, Color::Red);
+        assert_eq!(cell.bg, Color::Green);
+        assert!(cell.attrs.contains(Attrs::BOLD));
+        assert!(cell.attrs.contains(Attrs::UNDERLINE));
+    }
+
+    #[test]
+    fn sgr_bright_colors() {
+        let terminal = emulate("\x1b[91;102mA");
+        let cell = terminal.buffer[0][0];
+        assert_eq!(cell.fg, Color::BrightRed);
+        assert_eq!(cell.bg, Color::BrightGreen);
+    }
+
+    #[test]
+    fn sgr_reset_and_default() {
+        let terminal = emulate("\x1b[1;31mA\x1b[0mB\x1b[31m\x1b[39mC");
+        assert_eq!(terminal.buffer[0][0].fg, Color::Red);
+        assert!(terminal.buffer[0][0].attrs.contains(Attrs::BOLD));
+        assert_eq!(terminal.buffer[0][1].fg, Color::Default);
+        assert!(!terminal.buffer[0][1].attrs.contains(Attrs::BOLD));
+        assert_eq!(terminal.buffer[0][2].fg, Color::Default);
+    }
+
+    #[test]
+    fn sgr_per_attribute_reset_codes() {
+        let terminal = emulate("\x1b[1;3;4;7;9m\x1b[22;23;24;27;29mA");
+        let cell = terminal.buffer[0][0];
+        assert!(!cell.attrs.contains(Attrs::BOLD));
+        assert!(!cell.attrs.contains(Attrs::ITALIC));
+        assert!(!cell.attrs.contains(Attrs::UNDERLINE));
+        assert!(!cell.attrs.contains(Attrs::REVERSE));
+        assert!(!cell.attrs.contains(Attrs::STRIKETHROUGH));
+    }
+
+    /// A `Write` sink that records everything written to it, so tests can
+    /// assert on the bytes `send_keys` produced.
+    struct RecordingWriter(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for RecordingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn send_keys_encodes_terminal_sequences() {
+        let written = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut terminal = SimpleTerminal::new(Box::new(RecordingWriter(written.clone())));
+
+        terminal.send_keys(&[
+            Key::Char('a'),
+            Key::Enter,
+            Key::Backspace,
+            Key::Left,
+            Key::Home,
+            Key::Delete,
+            Key::Ctrl('c'),
+            Key::Ctrl('d'),
+            Key::Alt('x'),
+            Key::Esc,
+        ]);
+
+        assert_eq!(
+            &written.lock().unwrap()[..],
+            b"a\r\x7f\x1b[D\x1b[H\x1b[3~\x03\x04\x1bx\x1b"
+        );
+    }
+
+    /// Print `count` full-width lines (no newlines needed -- writing a whole
+    /// row's worth of columns wraps to the next row on its own, and wraps
+    /// into a scroll once the screen is full).
+    fn print_full_width_lines(terminal: &mut SimpleTerminal, count: usize) {
+        let mut parser = vte::Parser::new();
+        let width = terminal.width;
+        for i in 0..count {
+            let line = format!("{:<width$}", format!("line{i}"), width = width);
+            for byte in line.as_bytes() {
+                parser.advance(terminal, *byte);
+            }
+        }
+    }
+
+    #[test]
+    fn scrollback_keeps_lines_shifted_off_screen() {
+        let mut terminal = SimpleTerminal::new(Box::new(vec![]));
+        let height = terminal.height;
+        print_full_width_lines(&mut terminal, 30);
+
+        let scrollback: Vec<String> = terminal
+            .scrollback_lines()
+            .map(|row| row.iter().map(|c| c.ch).take(5).collect())
+            .collect();
+        assert_eq!(scrollback.len(), 30 - (height - 1));
+        assert_eq!(&scrollback[0], "line0");
+        assert_eq!(&scrollback[scrollback.len() - 1], "line6");
+    }
+
+    #[test]
+    fn scrollback_respects_capacity() {
+        let mut terminal = SimpleTerminal::with_scrollback_capacity(Box::new(vec![]), 2);
+        print_full_width_lines(&mut terminal, 30);
+
+        assert_eq!(terminal.scrollback_lines().count(), 2);
+    }
+
+    #[test]
+    fn line_feed_at_bottom_row_scrolls_into_scrollback() {
+        // Short lines terminated with `\r\n`, as real shell output would look,
+        // rather than full-width lines that wrap on their own.
+        let mut terminal = SimpleTerminal::new(Box::new(vec![]));
+        let height = terminal.height;
+        let mut parser = vte::Parser::new();
+        for i in 0..height + 3 {
+            let line = format!("line{i}\r\n");
+            for byte in line.as_bytes() {
+                parser.advance(&mut terminal, *byte);
+            }
+        }
+
+        let scrollback: Vec<String> = terminal
+            .scrollback_lines()
+            .map(|row| row.iter().map(|c| c.ch).take(5).collect())
+            .collect();
+        assert_eq!(scrollback.len(), 4);
+        assert_eq!(&scrollback[0], "line0");
+        assert_eq!(&scrollback[3], "line3");
+    }
+
+    #[test]
+    fn sgr_256_and_truecolor() {
+        let terminal = emulate("\x1b[38;5;202mA\x1b[48;2;10;20;30mB");
+        assert_eq!(terminal.buffer[0][0].fg, Color::Indexed(202));
+        assert_eq!(terminal.buffer[0][1].fg, Color::Indexed(202));
+        assert_eq!(terminal.buffer[0][1].bg, Color::Rgb(10, 20, 30));
+    }
+
+    #[test]
+    fn wait_until_drains_output_until_predicate_holds() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut terminal = SimpleTerminal::new(Box::new(Vec::new()));
+        terminal.rx = Some(rx);
+
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            tx.send(b"hello".to_vec()).unwrap();
+        });
+
+        let matched = terminal.wait_until(
+            |term| term.buffer[0][0].ch == 'h',
+            std::time::Duration::from_secs(1),
+        );
+        assert!(matched);
+        assert_eq!(terminal.buffer[0][0].ch, 'h');
+    }
+
+    #[test]
+    fn wait_until_gives_up_after_timeout() {
+        let (_tx, rx) = std::sync::mpsc::channel();
+        let mut terminal = SimpleTerminal::new(Box::new(Vec::new()));
+        terminal.rx = Some(rx);
+
+        let matched = terminal.wait_until(|_| false, std::time::Duration::from_millis(50));
+        assert!(!matched);
+    }
+
+    #[test]
+    fn osc_7_reports_current_dir() {
+        let terminal = emulate("\x1b]7;file://host/home/user/proj%20ect\x07");
+        assert_eq!(
+            terminal.current_dir(),
+            Some(std::path::Path::new("/home/user/proj ect"))
+        );
+    }
+
+    #[test]
+    fn osc_133_records_shell_markers() {
+        let terminal = emulate("\x1b]133;A\x07\x1b]133;B\x07\x1b]133;C\x07\x1b]133;D\x07");
+        assert_eq!(
+            terminal.shell_markers(),
+            &[
+                ShellMarker::PromptStart,
+                ShellMarker::CommandStart,
+                ShellMarker::OutputStart,
+                ShellMarker::CommandFinished,
+            ]
+        );
+    }
+
+    #[test]
+    fn osc_0_and_2_report_window_title() {
+        let terminal = emulate("\x1b]0;my title\x07");
+        assert_eq!(terminal.title(), Some("my title"));
+
+        let terminal = emulate("\x1b]2;another title\x07");
+        assert_eq!(terminal.title(), Some("another title"));
+    }
+
+    #[test]
+    fn alt_screen_swaps_and_restores_the_primary_screen() {
+        let terminal = emulate("main\x1b[?1049hALT");
+        assert_eq!(row_text(&terminal, 0, 0..3), "ALT");
+
+        let terminal = emulate("main\x1b[?1049hALT\x1b[?1049l");
+        assert_eq!(row_text(&terminal, 0, 0..4), "main");
+    }
+
+    #[test]
+    fn alt_screen_1049_restores_cursor() {
+        let terminal = emulate("\x1b[5;5H\x1b[?1049h\x1b[10;10H\x1b[?1049l");
+        assert_eq!(terminal.cursor, (4, 4));
+    }
+
+    #[test]
+    fn insert_and_delete_lines() {
+        let terminal = emulate("AAA\r\nBBB\r\nCCC\x1b[2;1H\x1b[L");
+        assert_eq!(row_text(&terminal, 0, 0..3), "AAA");
+        assert_eq!(row_text(&terminal, 1, 0..3), "   ");
+        assert_eq!(row_text(&terminal, 2, 0..3), "BBB");
+
+        let terminal = emulate("AAA\r\nBBB\r\nCCC\x1b[2;1H\x1b[M");
+        assert_eq!(row_text(&terminal, 0, 0..3), "AAA");
+        assert_eq!(row_text(&terminal, 1, 0..3), "CCC");
+        assert_eq!(row_text(&terminal, 2, 0..3), "   ");
+    }
+
+    #[test]
+    fn insert_and_delete_chars() {
+        let terminal = emulate("AAABBB\x1b[1;4H\x1b[3@");
+        assert_eq!(row_text(&terminal, 0, 0..9), "AAA   BBB");
+
+        let terminal = emulate("AAABBB\x1b[1;1H\x1b[3P");
+        assert_eq!(row_text(&terminal, 0, 0..6), "BBB   ");
+    }
+
+    #[test]
+    fn scroll_region_restricts_scroll_ops() {
+        let terminal = emulate("AAA\r\nBBB\r\nCCC\r\nDDD\x1b[2;3r\x1b[2;1H\x1b[1S");
+        // Only rows 2..=3 (1-indexed) scroll; row 1 (AAA) and row 4 (DDD) are untouched.
+        assert_eq!(row_text(&terminal, 0, 0..3), "AAA");
+        assert_eq!(row_text(&terminal, 1, 0..3), "CCC");
+        assert_eq!(row_text(&terminal, 2, 0..3), "   ");
+        assert_eq!(row_text(&terminal, 3, 0..3), "DDD");
+    }
+
+    #[test]
+    fn full_screen_scroll_up_pushes_evicted_row_into_scrollback() {
+        let terminal = emulate("AAA\r\nBBB\x1b[1S");
+        assert_eq!(row_text(&terminal, 0, 0..3), "BBB");
+
+        let scrollback: Vec<String> = terminal
+            .scrollback_lines()
+            .map(|row| row.iter().map(|c| c.ch).take(3).collect())
+            .collect();
+        assert_eq!(scrollback, vec!["AAA".to_string()]);
+    }
+
+    #[test]
+    fn decstbm_ignores_private_mode_restore() {
+        let terminal = emulate("\x1b[10;10H\x1b[?1r");
+        // `CSI ? 1 r` is XTRESTORE (restore DEC private modes), not DECSTBM --
+        // it must not reset the cursor or scroll region like a real `r` would.
+        assert_eq!(terminal.cursor, (9, 9));
+        assert_eq!(terminal.scroll_top, 0);
+        assert_eq!(terminal.scroll_bottom, terminal.height - 1);
+    }
+
+    #[test]
+    fn percent_decode_does_not_panic_on_malformed_multibyte_input() {
+        assert_eq!(super::percent_decode("%\u{4e16}"), "%\u{4e16}");
+    }
+
+    #[test]
+    fn with_size_sets_custom_geometry() {
+        let terminal = SimpleTerminal::with_size(10, 20, Box::new(vec![]));
+        assert_eq!(terminal.buffer.len(), 10);
+        assert_eq!(terminal.buffer[0].len(), 20);
+    }
+
+    #[test]
+    fn resize_preserves_content_and_clamps_cursor() {
+        let mut terminal = emulate("AB\r\nCD");
+        assert_eq!(terminal.cursor, (1, 2));
+
+        terminal.resize(1, 1);
+        assert_eq!(terminal.buffer.len(), 1);
+        assert_eq!(terminal.buffer[0].len(), 1);
+        assert_eq!(row_text(&terminal, 0, 0..1), "A");
+        assert_eq!(terminal.cursor, (0, 0));
+    }
+
+    #[test]
+    fn resize_also_resizes_the_stashed_alt_screen_buffer() {
+        let mut terminal = emulate("\x1b[?1049h");
+        assert!(terminal.alt_screen.is_some());
+
+        terminal.resize(2, 2);
+        let alt_screen = terminal.alt_screen.as_ref().unwrap();
+        assert_eq!(alt_screen.len(), 2);
+        assert_eq!(alt_screen[0].len(), 2);
+
+        // Restoring the primary screen after a resize must not panic
+        // indexing into a buffer with the old dimensions.
+        terminal.leave_alt_screen(false);
+        assert_eq!(terminal.buffer.len(), 2);
+        assert_eq!(terminal.buffer[0].len(), 2);
+    }
 }